@@ -1,9 +1,11 @@
 use std::{
-    env, fs,
-    path::{Path, PathBuf}
+    env, fs, io,
+    path::{Path, PathBuf},
+    time::Duration as StdDuration
 };
 
 use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Utc};
 use clap::Parser;
 use indoc::formatdoc;
 use jwt_simple::prelude::*;
@@ -23,6 +25,15 @@ use serde_json::Value as JsonValue;
 #[clap(about)]
 struct Opts {
 
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Path to a TOML configuration file. Defaults to 'github-app-token.toml'
+    /// in the current directory, if it exists. Values given here are overridden
+    /// by environment variables and command-line flags.
+    #[clap(long, env = "GITHUB_APP_TOKEN_CONFIG")]
+    config: Option<PathBuf>,
+
     /// The GitHub App ID
     #[clap(short = 'a', long, env = "GITHUB_APP_ID", conflicts_with = "app-id-file")]
     app_id: Option<String>,
@@ -35,9 +46,10 @@ struct Opts {
     #[clap(short = 'k', long, env = "GITHUB_APP_PRIVATE_KEY", conflicts_with = "private-key-file")]
     private_key: Option<String>,
 
-    /// The path a file containing the GitHub App private key, in PEM format
-    #[clap(short = 'K', long, env = "GITHUB_APP_PRIVATE_KEY_FILE", default_value = "private-key.pem")]
-    private_key_file: String,
+    /// The path a file containing the GitHub App private key, in PEM format.
+    /// Defaults to 'private-key.pem'.
+    #[clap(short = 'K', long, env = "GITHUB_APP_PRIVATE_KEY_FILE")]
+    private_key_file: Option<String>,
 
     /// The GitHub App ID
     #[clap(short = 'i', long, env = "GITHUB_APP_INSTALLATION_ID", conflicts_with = "installation-id-file")]
@@ -47,6 +59,41 @@ struct Opts {
     #[clap(short = 'I', long, env = "GITHUB_APP_INSTALLATION_ID_FILE", default_value = "installation-id")]
     installation_id_file: String,
 
+    /// The repository (owner/name) to look up the installation ID for, used
+    /// when neither --installation-id nor --installation-id-file resolve to anything
+    #[clap(long, conflicts_with = "org")]
+    repo: Option<String>,
+
+    /// The organization to look up the installation ID for, used when neither
+    /// --installation-id nor --installation-id-file resolve to anything
+    #[clap(long, conflicts_with = "repo")]
+    org: Option<String>,
+
+    /// Restrict the generated token to these repositories (comma-separated names,
+    /// not including the owner). If omitted, the token can access every repository
+    /// the installation can access.
+    #[clap(long, use_value_delimiter = true)]
+    repositories: Vec<String>,
+
+    /// Restrict the generated token to these repository IDs (comma-separated)
+    #[clap(long, use_value_delimiter = true)]
+    repository_ids: Vec<u64>,
+
+    /// Restrict the generated token to a permission, given as 'name=level'
+    /// (e.g. 'contents=read', 'pull_requests=write'). May be given multiple times.
+    #[clap(long = "permission", parse(try_from_str = parse_permission), multiple_occurrences = true)]
+    permissions: Vec<(String, String)>,
+
+    /// The minimum time-to-live, in seconds, a cached token must still have
+    /// before it is reused. Tokens with less than this left are re-requested.
+    #[clap(long, env = "GITHUB_APP_TOKEN_MIN_TTL", default_value = "300")]
+    min_ttl: u64,
+
+    /// Do not reuse or update the on-disk token or installation ID caches;
+    /// always re-resolve the installation and request a fresh token
+    #[clap(long, alias = "refresh")]
+    no_cache: bool,
+
     #[clap(flatten)]
     github: GithubOpts,
 
@@ -54,19 +101,66 @@ struct Opts {
     output: OutputOpts,
 }
 
+/// Parses a `key=value` command-line argument into a tuple.
+fn parse_permission(s: &str) -> Result<(String, String), String> {
+    s.split_once('=')
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .ok_or_else(|| format!("invalid permission '{}': expected 'name=level'", s))
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Act as a Git credential helper (see git-credential(1)), printing a
+    /// freshly minted or cached token instead of writing it to a file
+    Credential {
+        /// The credential helper operation Git is invoking
+        #[clap(arg_enum)]
+        operation: CredentialOperation,
+    },
+}
+
+#[derive(Debug, Clone, clap::ArgEnum)]
+enum CredentialOperation {
+    Get,
+    Store,
+    Erase,
+}
+
 #[derive(clap::Args, Debug)]
 struct GithubOpts {
 
-    /// The GitHub URL (used for writing Git basic auth config files)
-    #[clap(long = "github-url", env = "GITHUB_URL", default_value = "https://github.com")]
-    url: Url,
+    /// The GitHub URL (used for writing Git basic auth config files).
+    /// Defaults to 'https://github.com'.
+    #[clap(long = "github-url", env = "GITHUB_URL")]
+    url: Option<Url>,
 
-    /// The GitHub API URL (used for requesting the access token)
-    #[clap(long = "github-api-url", env = "GITHUB_API_URL", default_value = "https://api.github.com")]
-    api_url: Url,
+    /// The GitHub API URL (used for requesting the access token).
+    /// Defaults to 'https://api.github.com'.
+    #[clap(long = "github-api-url", env = "GITHUB_API_URL")]
+    api_url: Option<Url>,
+
+    /// Path to a PEM-encoded CA certificate to trust in addition to the system
+    /// root store, for GitHub Enterprise Server instances behind an internal CA
+    #[clap(long, env = "GITHUB_CA_CERT")]
+    ca_cert: Option<PathBuf>,
+
+    /// Disable TLS certificate verification. Dangerous; only use against test environments
+    #[clap(long)]
+    danger_accept_invalid_certs: bool,
 
 }
 
+/// `GithubOpts`, with `url` and `api_url` resolved to their final values.
+struct ResolvedGithubOpts {
+    url: Url,
+    api_url: Url,
+    ca_cert: Option<PathBuf>,
+    danger_accept_invalid_certs: bool,
+}
+
+const DEFAULT_GITHUB_URL: &str = "https://github.com";
+const DEFAULT_GITHUB_API_URL: &str = "https://api.github.com";
+
 #[derive(clap::Args, Debug)]
 struct OutputOpts {
     /// Print the outcome to standard output
@@ -86,7 +180,8 @@ struct OutputOpts {
     force: bool,
 }
 
-#[derive(Debug, Clone, clap::ArgEnum)]
+#[derive(Debug, Clone, clap::ArgEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 enum PrintStyle {
     /// Just print the token string
     Token,
@@ -99,30 +194,127 @@ impl std::default::Default for PrintStyle {
 }
 
 struct ParsedOpts {
+    command: Option<Command>,
     app_id: String,
     private_key: String,
-    installation_id: String,
-    github: GithubOpts,
+    installation_id: Option<String>,
+    repo: Option<String>,
+    org: Option<String>,
+    repositories: Vec<String>,
+    repository_ids: Vec<u64>,
+    permissions: Vec<(String, String)>,
+    min_ttl: u64,
+    no_cache: bool,
+    github: ResolvedGithubOpts,
     output: OutputOpts,
 }
 
-fn from_string_opt(description: &str, value: Option<String>, file_path: String) -> Result<String> {
-    match value {
+/// The subset of `Opts`/`GithubOpts`/`OutputOpts` that may be set from a TOML
+/// configuration file, so that teams can check a single declarative file into
+/// a repo instead of juggling CLI flags, env vars and the various `*-file` defaults.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ConfigFile {
+    app_id: Option<String>,
+    private_key_file: Option<String>,
+    installation_id: Option<String>,
+    github: Option<GithubConfigFile>,
+    output: Option<OutputConfigFile>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+struct GithubConfigFile {
+    url: Option<Url>,
+    api_url: Option<Url>,
+}
+
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct OutputConfigFile {
+    print: Option<PrintStyle>,
+    write_to: Option<PathBuf>,
+    git_config: Option<PathBuf>,
+}
+
+/// Loads the configuration file at `path`, or the default 'github-app-token.toml'
+/// in the current directory if `path` is `None` and that file exists.
+fn load_config(path: Option<&Path>) -> Result<ConfigFile> {
+    // An explicitly-given --config that happens to match the default filename
+    // must still error when missing, so branch on `path` itself, not the
+    // resolved path value.
+    let path = match path {
+        Some(path) if path.exists() => path,
+        Some(path) => return Err(anyhow!("Config file not found: {}", path.display())),
+        None => {
+            let default_path = Path::new("github-app-token.toml");
+            if !default_path.exists() {
+                return Ok(ConfigFile::default());
+            }
+            default_path
+        }
+    };
+
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+
+    toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse config file: {}", path.display()))
+}
+
+fn from_string_opt(description: &str, value: Option<String>, config_value: Option<String>, file_path: String) -> Result<String> {
+    match value.or(config_value) {
         Some(s) => Ok(s),
         None => fs::read_to_string(&file_path)
             .with_context(|| format!("Failed to read {} from file: {}", description, file_path))
     }
 }
 
+/// Like `from_string_opt`, but for values that may legitimately be absent
+/// (in which case they are resolved some other way later on).
+fn from_string_opt_optional(value: Option<String>, config_value: Option<String>, file_path: &str) -> Option<String> {
+    value.or(config_value).or_else(|| fs::read_to_string(file_path).ok())
+}
+
 impl Opts {
 
     fn finish_parsing(self) -> Result<ParsedOpts> {
+        let config = load_config(self.config.as_deref())?;
+
+        let private_key_file = self.private_key_file
+            .or(config.private_key_file.clone())
+            .unwrap_or_else(|| "private-key.pem".to_string());
+
+        let github_config = config.github.unwrap_or_default();
+
         Ok(ParsedOpts {
-            app_id: from_string_opt("App ID", self.app_id, self.app_id_file)?,
-            private_key: from_string_opt("private key", self.private_key, self.private_key_file)?,
-            installation_id: from_string_opt("App installation ID", self.installation_id, self.installation_id_file)?,
-            github: self.github,
-            output: self.output,
+            command: self.command,
+            app_id: from_string_opt("App ID", self.app_id, config.app_id, self.app_id_file)?,
+            private_key: from_string_opt("private key", self.private_key, None, private_key_file)?,
+            installation_id: from_string_opt_optional(self.installation_id, config.installation_id, &self.installation_id_file),
+            repo: self.repo,
+            org: self.org,
+            repositories: self.repositories,
+            repository_ids: self.repository_ids,
+            permissions: self.permissions,
+            min_ttl: self.min_ttl,
+            no_cache: self.no_cache,
+            github: ResolvedGithubOpts {
+                url: self.github.url.or(github_config.url)
+                    .unwrap_or_else(|| Url::parse(DEFAULT_GITHUB_URL).unwrap()),
+                api_url: self.github.api_url.or(github_config.api_url)
+                    .unwrap_or_else(|| Url::parse(DEFAULT_GITHUB_API_URL).unwrap()),
+                ca_cert: self.github.ca_cert,
+                danger_accept_invalid_certs: self.github.danger_accept_invalid_certs,
+            },
+            output: {
+                let output_config = config.output.unwrap_or_default();
+                OutputOpts {
+                    print: self.output.print.or(output_config.print),
+                    write_to: self.output.write_to.or(output_config.write_to),
+                    git_config: self.output.git_config.or(output_config.git_config),
+                    force: self.output.force,
+                }
+            },
         })
     }
 
@@ -135,20 +327,14 @@ fn main() -> Result<()> {
     // Parse command line
     let opts = Opts::parse().finish_parsing()?;
 
-    // Send token request to GitHub
-    let response: JsonValue = http::Client::new()
-        .post(opts.github.api_url.join(&format!("app/installations/{}/access_tokens", opts.installation_id))?)
-        .header(USER_AGENT, &opts.app_id)
-        .header(ACCEPT, "application/vnd.github.v3+json")
-        .bearer_auth(generate_app_jwt(&opts.app_id, &opts.private_key)?)
-        .send()?
-        .error_for_status()?
-        .json()?;
+    // Build the HTTP client, trusting any custom CA certificate that was given
+    let client = build_http_client(&opts.github)?;
 
-    // Extract token from response
-    let token: String = response
-        .get("token").and_then(JsonValue::as_str).map(String::from)
-        .ok_or_else(|| anyhow!("Response from GitHub is missing the 'token' field: {}", response))?;
+    if let Some(Command::Credential { operation }) = opts.command {
+        return run_credential_helper(operation, &opts, &client);
+    }
+
+    let (token, response) = get_access_token(&opts, &client)?;
 
     // Write out to file (if requested)
     if let Some(ref file) = opts.output.write_to {
@@ -201,6 +387,454 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Builds the JSON body for the `access_tokens` request, scoping the generated
+/// token to the requested repositories and/or permissions. Returns `None` when
+/// no scoping was requested, so that the full-access request sent today is
+/// left untouched.
+fn access_token_request_body(opts: &ParsedOpts) -> Option<JsonValue> {
+    if opts.repositories.is_empty() && opts.repository_ids.is_empty() && opts.permissions.is_empty() {
+        return None;
+    }
+
+    let mut body = serde_json::Map::new();
+
+    if !opts.repositories.is_empty() {
+        body.insert("repositories".to_string(), JsonValue::from(opts.repositories.clone()));
+    }
+
+    if !opts.repository_ids.is_empty() {
+        body.insert("repository_ids".to_string(), JsonValue::from(opts.repository_ids.clone()));
+    }
+
+    if !opts.permissions.is_empty() {
+        let permissions: serde_json::Map<String, JsonValue> = opts.permissions.iter()
+            .map(|(k, v)| (k.clone(), JsonValue::from(v.clone())))
+            .collect();
+        body.insert("permissions".to_string(), JsonValue::Object(permissions));
+    }
+
+    Some(JsonValue::Object(body))
+}
+
+/// Builds the HTTP client used for all GitHub requests, trusting `github.ca_cert`
+/// (if given) in addition to the system root store.
+fn build_http_client(github: &ResolvedGithubOpts) -> Result<http::Client> {
+    let mut builder = http::ClientBuilder::new();
+
+    if let Some(ref ca_cert) = github.ca_cert {
+        let pem = fs::read(ca_cert)
+            .with_context(|| format!("Failed to read CA certificate: {}", ca_cert.display()))?;
+        builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+    }
+
+    if github.danger_accept_invalid_certs {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    builder.build().context("Failed to build HTTP client.")
+}
+
+// -----------------------------------------------------------------------------
+// Token retrieval
+// -----------------------------------------------------------------------------
+
+/// Resolves the installation ID (if needed), returns a still-valid cached
+/// token if one exists, or otherwise requests and caches a fresh one.
+/// Returns the token string and the full `access_tokens` response.
+fn get_access_token(opts: &ParsedOpts, client: &http::Client) -> Result<(String, JsonValue)> {
+
+    // Lazily signed below, and reused if both the installation ID and the
+    // access token still need to be resolved in the same invocation
+    let mut jwt: Option<String> = None;
+
+    // Resolve the installation ID, looking it up from --repo/--org if it wasn't given directly
+    let installation_id = match opts.installation_id {
+        Some(ref id) => id.clone(),
+        None => resolve_and_cache_installation_id(client, opts, &mut jwt)?,
+    };
+
+    // Look for a still-valid cached token before calling GitHub
+    let cache_file = token_cache_file(opts, &installation_id);
+    let cached_response = if opts.no_cache {
+        None
+    } else {
+        cache_file.as_deref().and_then(|f| read_cached_token(f, StdDuration::from_secs(opts.min_ttl)))
+    };
+
+    let response: JsonValue = match cached_response {
+        Some(response) => response,
+        None => {
+            // Reuses the JWT signed above while resolving the installation ID, if any
+            let jwt = match jwt {
+                Some(jwt) => jwt,
+                None => generate_app_jwt(&opts.app_id, &opts.private_key)?,
+            };
+
+            // Send token request to GitHub
+            let mut request = client
+                .post(opts.github.api_url.join(&format!("app/installations/{}/access_tokens", installation_id))?)
+                .header(USER_AGENT, &opts.app_id)
+                .header(ACCEPT, "application/vnd.github.v3+json")
+                .bearer_auth(&jwt);
+
+            if let Some(body) = access_token_request_body(opts) {
+                request = request.json(&body);
+            }
+
+            let response = request.send()?;
+
+            // The installation ID may have come from a stale cache entry (e.g.
+            // the app was reinstalled on the repo/org); drop it so the next
+            // invocation re-resolves instead of failing forever.
+            if !response.status().is_success() && opts.installation_id.is_none() && !opts.no_cache {
+                if let Some(ref f) = installation_id_cache_file(opts) {
+                    let _ = fs::remove_file(f);
+                }
+            }
+
+            let response: JsonValue = response
+                .error_for_status()?
+                .json()?;
+
+            // A valid token was already obtained above, so don't fail the command
+            // just because the cache (often unwritable in CI/containers) couldn't be updated
+            if !opts.no_cache {
+                if let Some(ref f) = cache_file {
+                    if let Err(e) = write_cached_token(f, &response) {
+                        eprintln!("Warning: failed to write token cache: {:#}", e);
+                    }
+                }
+            }
+
+            response
+        }
+    };
+
+    let token: String = response
+        .get("token").and_then(JsonValue::as_str).map(String::from)
+        .ok_or_else(|| anyhow!("Response from GitHub is missing the 'token' field: {}", response))?;
+
+    Ok((token, response))
+}
+
+// -----------------------------------------------------------------------------
+// Git credential helper
+// -----------------------------------------------------------------------------
+
+/// Implements the Git credential helper protocol
+/// (<https://git-scm.com/docs/git-credential#_custom_helpers>): reads the
+/// request attributes Git sends on stdin, and for a `get` prints back a
+/// freshly minted or cached token as the password. `store`/`erase` are no-ops,
+/// since tokens are never written anywhere Git expects to manage them itself.
+fn run_credential_helper(operation: CredentialOperation, opts: &ParsedOpts, client: &http::Client) -> Result<()> {
+    let attributes = read_credential_attributes()?;
+
+    if let CredentialOperation::Get = operation {
+        // Only hand back a token if Git is asking about the configured GitHub
+        // host; otherwise this helper would leak the token to any other
+        // protocol/host Git happens to ask about, if registered globally.
+        if !credential_request_matches_github(&attributes, &opts.github.url) {
+            return Ok(());
+        }
+
+        let (token, _) = get_access_token(opts, client)?;
+        println!("username=x-access-token");
+        println!("password={}", token);
+    }
+
+    Ok(())
+}
+
+/// Whether the `protocol`/`host` attributes Git sent match `github_url`,
+/// comparing hostnames case-insensitively (per DNS) and ports by their
+/// effective value (Git omits the port when it's the protocol's default).
+fn credential_request_matches_github(attributes: &[(String, String)], github_url: &Url) -> bool {
+    let attr = |name: &str| attributes.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str());
+
+    let protocol = match attr("protocol") {
+        Some(p) => p,
+        None => return false,
+    };
+
+    if !protocol.eq_ignore_ascii_case(github_url.scheme()) {
+        return false;
+    }
+
+    let (host, port) = match attr("host") {
+        Some(h) => match h.rsplit_once(':').and_then(|(host, port)| port.parse::<u16>().ok().map(|p| (host, Some(p)))) {
+            Some((host, port)) => (host, port),
+            None => (h, None),
+        },
+        None => return false,
+    };
+    // Git sends IPv6 literals in brackets, and `Url::host_str()` keeps them too,
+    // so the two compare directly
+
+    // `protocol` already matched `github_url.scheme()` above (case-insensitively),
+    // so its default port is the same one `github_url` itself would fall back to.
+    let port = port.or_else(|| github_url.port_or_known_default());
+
+    github_url.host_str().map(|h| h.eq_ignore_ascii_case(host)) == Some(true)
+        && port == github_url.port_or_known_default()
+}
+
+/// Reads the `key=value` request attributes Git writes to stdin, up to the
+/// terminating blank line.
+fn read_credential_attributes() -> Result<Vec<(String, String)>> {
+    use std::io::BufRead;
+
+    let mut attributes = Vec::new();
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            attributes.push((key.to_string(), value.to_string()));
+        }
+    }
+
+    Ok(attributes)
+}
+
+// -----------------------------------------------------------------------------
+// Installation resolution
+// -----------------------------------------------------------------------------
+
+/// Looks up the installation ID for `opts.repo` or `opts.org`, or, if neither
+/// is given, for the app's sole installation.
+fn resolve_installation_id(client: &http::Client, opts: &ParsedOpts, jwt: &str) -> Result<String> {
+    let path = match (&opts.repo, &opts.org) {
+        (Some(repo), _) => format!("repos/{}/installation", repo),
+        (_, Some(org)) => format!("orgs/{}/installation", org),
+        (None, None) => return resolve_sole_installation_id(client, opts, jwt),
+    };
+
+    let response: JsonValue = client.get(opts.github.api_url.join(&path)?)
+        .header(USER_AGENT, &opts.app_id)
+        .header(ACCEPT, "application/vnd.github.v3+json")
+        .bearer_auth(jwt)
+        .send()?
+        .error_for_status()?
+        .json()?;
+
+    installation_id_from(&response)
+}
+
+/// Looks up the installation ID of the app's single installation. Fails if
+/// there is none, or if there is more than one and so the choice is ambiguous.
+fn resolve_sole_installation_id(client: &http::Client, opts: &ParsedOpts, jwt: &str) -> Result<String> {
+    let installations: Vec<JsonValue> = client.get(opts.github.api_url.join("app/installations")?)
+        .header(USER_AGENT, &opts.app_id)
+        .header(ACCEPT, "application/vnd.github.v3+json")
+        .bearer_auth(jwt)
+        .send()?
+        .error_for_status()?
+        .json()?;
+
+    match installations.as_slice() {
+        [installation] => installation_id_from(installation),
+        [] => Err(anyhow!("App has no installations, and none was specified with --installation-id, --repo or --org.")),
+        _ => Err(anyhow!("App has multiple installations; specify one with --installation-id, --repo or --org.")),
+    }
+}
+
+fn installation_id_from(installation: &JsonValue) -> Result<String> {
+    installation.get("id").and_then(JsonValue::as_u64).map(|id| id.to_string())
+        .ok_or_else(|| anyhow!("Response from GitHub is missing the installation 'id' field: {}", installation))
+}
+
+/// Resolves the installation ID for `opts.repo`/`opts.org` (or the app's sole
+/// installation), consulting a small on-disk cache first. Without this, a
+/// `--repo`/`--org` invocation would always make a live `.../installation`
+/// request even when the access token it's needed for is already cached.
+///
+/// If a network request is needed, the app JWT it requires is left in `jwt`
+/// so callers that go on to request an access token don't sign a second one.
+fn resolve_and_cache_installation_id(client: &http::Client, opts: &ParsedOpts, jwt: &mut Option<String>) -> Result<String> {
+    let cache_file = installation_id_cache_file(opts);
+
+    if !opts.no_cache {
+        if let Some(id) = cache_file.as_deref().and_then(read_cached_installation_id) {
+            return Ok(id);
+        }
+    }
+
+    if jwt.is_none() {
+        *jwt = Some(generate_app_jwt(&opts.app_id, &opts.private_key)?);
+    }
+    let installation_id = resolve_installation_id(client, opts, jwt.as_deref().unwrap())?;
+
+    if !opts.no_cache {
+        if let Some(ref f) = cache_file {
+            if let Err(e) = write_cached_installation_id(f, &installation_id) {
+                eprintln!("Warning: failed to write installation ID cache: {:#}", e);
+            }
+        }
+    }
+
+    Ok(installation_id)
+}
+
+/// The on-disk location the installation ID resolved for `opts.repo`/`opts.org`
+/// would be cached at, or `None` if this platform has no cache directory.
+fn installation_id_cache_file(opts: &ParsedOpts) -> Option<PathBuf> {
+    cache_file(&format!("{}.installation", installation_id_cache_key(opts)))
+}
+
+/// A stable key identifying the app and the `--repo`/`--org` (or lack of
+/// either) being resolved, so that different targets don't clobber each other.
+fn installation_id_cache_key(opts: &ParsedOpts) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    opts.repo.hash(&mut hasher);
+    opts.org.hash(&mut hasher);
+
+    format!("{}-{:016x}", opts.app_id, hasher.finish())
+}
+
+/// Reads a cached installation ID from `path`, if one was cached there.
+fn read_cached_installation_id(path: &Path) -> Option<String> {
+    let id = fs::read_to_string(path).ok()?;
+    let id = id.trim();
+
+    if id.is_empty() { None } else { Some(id.to_string()) }
+}
+
+/// Caches a resolved installation ID to `path` for reuse by later invocations.
+/// An installation ID isn't a secret itself, but it shares a cache directory
+/// with cached tokens, so that directory is still kept private to the owner.
+fn write_cached_installation_id(path: &Path, installation_id: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+        set_private_permissions(dir, 0o700)?;
+    }
+
+    fs::write(path, installation_id)?;
+    Ok(())
+}
+
+// -----------------------------------------------------------------------------
+// Token caching
+// -----------------------------------------------------------------------------
+
+/// The on-disk location of `name` within this tool's cache directory, or
+/// `None` if this platform has no cache directory.
+fn cache_file(name: &str) -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("github-app-token").join(name))
+}
+
+/// The on-disk location a token for this app, installation and scope would be
+/// cached at, or `None` if this platform has no cache directory.
+fn token_cache_file(opts: &ParsedOpts, installation_id: &str) -> Option<PathBuf> {
+    cache_file(&format!("{}.json", token_cache_key(opts, installation_id)))
+}
+
+/// A stable key identifying the app, installation and requested scope, so that
+/// differently-scoped tokens for the same installation don't clobber each other.
+fn token_cache_key(opts: &ParsedOpts, installation_id: &str) -> String {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut repositories = opts.repositories.clone();
+    repositories.sort();
+
+    let mut repository_ids = opts.repository_ids.clone();
+    repository_ids.sort();
+
+    let mut permissions = opts.permissions.clone();
+    permissions.sort();
+
+    let mut hasher = DefaultHasher::new();
+    installation_id.hash(&mut hasher);
+    repositories.hash(&mut hasher);
+    repository_ids.hash(&mut hasher);
+    permissions.hash(&mut hasher);
+
+    format!("{}-{:016x}", opts.app_id, hasher.finish())
+}
+
+/// Reads a cached `access_tokens` response from `path`, if one exists and still
+/// has at least `min_ttl` left before the token it contains expires.
+fn read_cached_token(path: &Path, min_ttl: StdDuration) -> Option<JsonValue> {
+    let response: JsonValue = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+
+    let expires_at: DateTime<Utc> = response.get("expires_at")?.as_str()?.parse().ok()?;
+    let min_ttl = chrono::Duration::from_std(min_ttl).ok()?;
+
+    if expires_at > Utc::now() + min_ttl {
+        Some(response)
+    } else {
+        None
+    }
+}
+
+/// Caches an `access_tokens` response to `path` for reuse by later invocations.
+/// The cache directory and file are created private to the current user, since
+/// the cache holds a live GitHub App installation token.
+fn write_cached_token(path: &Path, response: &JsonValue) -> Result<()> {
+    use std::io::Write;
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+        set_private_permissions(dir, 0o700)?;
+    }
+
+    let mut file = private_file_options().open(path)?;
+    // Re-assert the permissions on the open handle before writing any data: the
+    // file may already have existed (e.g. from before this cache was made
+    // private), in which case the mode passed to `open` above is ignored by the OS.
+    restrict_to_owner(&file)?;
+    file.write_all(&serde_json::to_vec(response)?)?;
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn restrict_to_owner(file: &fs::File) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn restrict_to_owner(_file: &fs::File) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn private_file_options() -> fs::OpenOptions {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true).mode(0o600);
+    options
+}
+
+#[cfg(not(unix))]
+fn private_file_options() -> fs::OpenOptions {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    options
+}
+
+#[cfg(unix)]
+fn set_private_permissions(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_private_permissions(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
 /// Generates a GitHub App JWT for the given app ID, signed using the given key.
 /// 
 /// The generated JWT will have a 10-minute expiry.
@@ -230,3 +864,60 @@ where
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attrs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn rejects_mismatched_protocol() {
+        let github_url = Url::parse("https://github.com").unwrap();
+        let attributes = attrs(&[("protocol", "http"), ("host", "github.com")]);
+
+        assert!(!credential_request_matches_github(&attributes, &github_url));
+    }
+
+    #[test]
+    fn matches_default_port_when_omitted_on_both_sides() {
+        let github_url = Url::parse("https://github.com").unwrap();
+        let attributes = attrs(&[("protocol", "https"), ("host", "github.com")]);
+
+        assert!(credential_request_matches_github(&attributes, &github_url));
+    }
+
+    #[test]
+    fn matches_explicit_port_equal_to_default() {
+        let github_url = Url::parse("https://github.com").unwrap();
+        let attributes = attrs(&[("protocol", "https"), ("host", "github.com:443")]);
+
+        assert!(credential_request_matches_github(&attributes, &github_url));
+    }
+
+    #[test]
+    fn rejects_mismatched_port() {
+        let github_url = Url::parse("https://github.example.com:8443").unwrap();
+        let attributes = attrs(&[("protocol", "https"), ("host", "github.example.com:9443")]);
+
+        assert!(!credential_request_matches_github(&attributes, &github_url));
+    }
+
+    #[test]
+    fn matches_ipv6_host_with_brackets() {
+        let github_url = Url::parse("https://[::1]:8443").unwrap();
+        let attributes = attrs(&[("protocol", "https"), ("host", "[::1]:8443")]);
+
+        assert!(credential_request_matches_github(&attributes, &github_url));
+    }
+
+    #[test]
+    fn matches_host_and_protocol_case_insensitively() {
+        let github_url = Url::parse("https://github.example.com").unwrap();
+        let attributes = attrs(&[("protocol", "HTTPS"), ("host", "GitHub.Example.Com")]);
+
+        assert!(credential_request_matches_github(&attributes, &github_url));
+    }
+}
+